@@ -1,55 +1,563 @@
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use argon2::Argon2;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chacha20poly1305::ChaCha20Poly1305;
 use crc::crc32;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+fn compute_checksum(kind: ChecksumKind, data: &[u8]) -> u32 {
+    match kind {
+        ChecksumKind::Crc32Ieee => crc32::checksum_ieee(data),
+        ChecksumKind::Crc32c => crc32c::crc32c(data),
+    }
+}
+
+// CRC variant protecting each record, recorded in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumKind {
+    Crc32Ieee,
+    Crc32c,
+}
+
+impl ChecksumKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            ChecksumKind::Crc32Ieee => 0,
+            ChecksumKind::Crc32c => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(ChecksumKind::Crc32Ieee),
+            1 => Ok(ChecksumKind::Crc32c),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown checksum kind byte {}", other),
+            )),
+        }
+    }
+}
+
+// Value compression algorithm, recorded in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionKind {
+    None,
+    Zstd,
+    Deflate,
+}
+
+impl CompressionKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            CompressionKind::None => 0,
+            CompressionKind::Zstd => 1,
+            CompressionKind::Deflate => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(CompressionKind::None),
+            1 => Ok(CompressionKind::Zstd),
+            2 => Ok(CompressionKind::Deflate),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown compression kind byte {}", other),
+            )),
+        }
+    }
+}
+
+fn compress_value(kind: CompressionKind, data: &[u8]) -> io::Result<ByteString> {
+    match kind {
+        CompressionKind::None => Ok(data.to_vec()),
+        CompressionKind::Zstd => zstd::encode_all(data, 0),
+        CompressionKind::Deflate => {
+            let mut encoder =
+                flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+fn decompress_value(kind: CompressionKind, data: &[u8]) -> io::Result<ByteString> {
+    match kind {
+        CompressionKind::None => Ok(data.to_vec()),
+        CompressionKind::Zstd => zstd::decode_all(data),
+        CompressionKind::Deflate => {
+            let mut decoder = flate2::read::DeflateDecoder::new(data);
+            let mut out = ByteString::new();
+            decoder.read_to_end(&mut out)?;
+            Ok(out)
+        }
+    }
+}
+
+// Values smaller than this are never compressed, since the AEAD tag, CRC, and
+// compression headers would outweigh the savings.
+const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
+
+// Small builder for the `open` settings that are too niche to deserve their
+// own dedicated constructor (see `open_encrypted`, `open_with_checksum`).
+#[derive(Debug, Clone, Copy)]
+pub struct SimpleKVOptions {
+    compression_kind: CompressionKind,
+    compression_threshold: usize,
+}
+
+impl Default for SimpleKVOptions {
+    fn default() -> Self {
+        SimpleKVOptions {
+            compression_kind: CompressionKind::None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        }
+    }
+}
+
+impl SimpleKVOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compression(mut self, kind: CompressionKind) -> Self {
+        self.compression_kind = kind;
+        self
+    }
+
+    pub fn compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+}
 
 type ByteString = Vec<u8>;
 type ByteStr = [u8];
 
+// Key under which the second binary persists its on-disk index blob. Compaction
+// drops this record, since the offsets it contains no longer hold afterwards.
+const INDEX_KEY: &ByteStr = b"+index";
+
+// File signature, modeled on the PNG signature scheme: a non-ASCII first byte
+// (rules out accidental handling as text), "SKV" so a hex dump instantly
+// identifies the format, and a CR-LF-EOF sequence that catches any transfer
+// that has mangled line endings or truncated the file.
+const HEADER_MAGIC: [u8; 8] = [0x89, b'S', b'K', b'V', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Bumped whenever the on-disk record layout changes incompatibly.
+const FORMAT_VERSION: u8 = 1;
+
+// Length of the Argon2id salt stored in the header, used whether or not the
+// store is actually encrypted (zero-filled when it is not).
+const SALT_LEN: usize = 16;
+
+// Every record offset is stored and looked up relative to this point.
+const HEADER_LEN: u64 = HEADER_MAGIC.len() as u64 + 1 + 1 + SALT_LEN as u64 + 1 + 1;
+
+// AEAD nonce and authentication tag sizes, shared by AES-256-GCM and
+// ChaCha20-Poly1305.
+const NONCE_LEN: usize = 12;
+const AEAD_TAG_LEN: usize = 16;
+
+fn write_header(
+    f: &mut File,
+    enc_type: EncryptionType,
+    salt: &[u8; SALT_LEN],
+    checksum_kind: ChecksumKind,
+    compression_kind: CompressionKind,
+) -> io::Result<()> {
+    f.write_all(&HEADER_MAGIC)?;
+    f.write_all(&[FORMAT_VERSION])?;
+    f.write_all(&[enc_type.to_byte()])?;
+    f.write_all(salt)?;
+    f.write_all(&[checksum_kind.to_byte()])?;
+    f.write_all(&[compression_kind.to_byte()])?;
+    Ok(())
+}
+
+// Read and validate the signature/version, then return the encryption,
+// checksum and compression descriptors without disturbing the caller's
+// notion of where records start.
+fn read_header_descriptor(
+    f: &mut File,
+) -> io::Result<(EncryptionType, [u8; SALT_LEN], ChecksumKind, CompressionKind)> {
+    f.seek(SeekFrom::Start(0))?;
+
+    let mut magic = [0u8; HEADER_MAGIC.len()];
+    f.read_exact(&mut magic)?;
+    if magic != HEADER_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a SimpleKV file: bad signature",
+        ));
+    }
+
+    let mut version = [0u8; 1];
+    f.read_exact(&mut version)?;
+    if version[0] != FORMAT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported SimpleKV format version {}", version[0]),
+        ));
+    }
+
+    let mut enc_byte = [0u8; 1];
+    f.read_exact(&mut enc_byte)?;
+    let enc_type = EncryptionType::from_byte(enc_byte[0])?;
+
+    let mut salt = [0u8; SALT_LEN];
+    f.read_exact(&mut salt)?;
+
+    let mut checksum_byte = [0u8; 1];
+    f.read_exact(&mut checksum_byte)?;
+    let checksum_kind = ChecksumKind::from_byte(checksum_byte[0])?;
+
+    let mut compression_byte = [0u8; 1];
+    f.read_exact(&mut compression_byte)?;
+    let compression_kind = CompressionKind::from_byte(compression_byte[0])?;
+
+    Ok((enc_type, salt, checksum_kind, compression_kind))
+}
+
+// AEAD cipher used to encrypt record bodies at rest, recorded in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionType {
+    None,
+    AesGcm,
+    ChaCha20Poly1305,
+}
+
+impl EncryptionType {
+    fn to_byte(self) -> u8 {
+        match self {
+            EncryptionType::None => 0,
+            EncryptionType::AesGcm => 1,
+            EncryptionType::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(EncryptionType::None),
+            1 => Ok(EncryptionType::AesGcm),
+            2 => Ok(EncryptionType::ChaCha20Poly1305),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown encryption type byte {}", other),
+            )),
+        }
+    }
+}
+
+// Derive a 256-bit key from a passphrase and the per-file salt using Argon2id.
+fn derive_key(passphrase: &[u8], salt: &[u8; SALT_LEN]) -> io::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase, salt, &mut key)
+        .map_err(|err| io::Error::other(format!("key derivation failed: {}", err)))?;
+    Ok(key)
+}
+
+fn encrypt_body(
+    enc_type: EncryptionType,
+    key: &[u8; 32],
+    nonce: &[u8; NONCE_LEN],
+    plaintext: &[u8],
+) -> io::Result<ByteString> {
+    let result = match enc_type {
+        EncryptionType::None => return Ok(plaintext.to_vec()),
+        EncryptionType::AesGcm => {
+            let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+            cipher.encrypt(GenericArray::from_slice(nonce), plaintext)
+        }
+        EncryptionType::ChaCha20Poly1305 => {
+            let cipher = ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key));
+            cipher.encrypt(chacha20poly1305::Nonce::from_slice(nonce), plaintext)
+        }
+    };
+
+    result.map_err(|err| io::Error::other(format!("encryption failed: {}", err)))
+}
+
+fn decrypt_body(
+    enc_type: EncryptionType,
+    key: &[u8; 32],
+    nonce: &[u8; NONCE_LEN],
+    ciphertext: &[u8],
+) -> io::Result<ByteString> {
+    let result = match enc_type {
+        EncryptionType::None => return Ok(ciphertext.to_vec()),
+        EncryptionType::AesGcm => {
+            let cipher = Aes256Gcm::new(GenericArray::from_slice(key));
+            cipher.decrypt(GenericArray::from_slice(nonce), ciphertext)
+        }
+        EncryptionType::ChaCha20Poly1305 => {
+            let cipher = ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(key));
+            cipher.decrypt(chacha20poly1305::Nonce::from_slice(nonce), ciphertext)
+        }
+    };
+
+    result.map_err(|err| io::Error::other(format!("decryption failed: {}", err)))
+}
+
+// Record type, written as a single byte right after the checksum/length header.
+// Distinguishes a deleted key (TOMBSTONE) from a key whose real value happens
+// to be empty (LIVE).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordType {
+    Live,
+    Tombstone,
+}
+
+impl RecordType {
+    fn to_byte(self) -> u8 {
+        match self {
+            RecordType::Live => 0,
+            RecordType::Tombstone => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> io::Result<Self> {
+        match byte {
+            0 => Ok(RecordType::Live),
+            1 => Ok(RecordType::Tombstone),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown record type byte {}", other),
+            )),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct KeyValuePair {
     pub key: ByteString,
     pub value: ByteString,
+
+    // Not exposed publicly: LIVE vs TOMBSTONE, used to decide whether this
+    // record should still be visible once it is loaded or scanned.
+    record_type: RecordType,
 }
 
 #[derive(Debug)]
 pub struct SimpleKV {
     f: File,
+    path: PathBuf,
 
     // Mapping between keys and file locations.
     pub index: HashMap<ByteString, u64>,
+
+    enc_type: EncryptionType,
+    salt: [u8; SALT_LEN],
+    key: Option<[u8; 32]>,
+    checksum_kind: ChecksumKind,
+    compression_kind: CompressionKind,
+    compression_threshold: usize,
 }
 
 impl SimpleKV {
     pub fn open(path: &Path) -> io::Result<Self> {
-        let f = OpenOptions::new()
+        Self::open_with(path, SimpleKVOptions::default())
+    }
+
+    // Open (or create) a store with the compression settings in `options`.
+    // `open` is a thin wrapper around this with all defaults (no compression).
+    pub fn open_with(path: &Path, options: SimpleKVOptions) -> io::Result<Self> {
+        let mut f = OpenOptions::new()
             .read(true)
-            .write(true)
             .create(true)
             .append(true)
             .open(path)?;
 
+        let salt = [0u8; SALT_LEN];
+
+        // A brand-new, zero-length file gets the header up front so that every
+        // reader (including this process, once `load` runs) sees a well-formed
+        // file instead of having to special-case an empty one. An existing
+        // file keeps whatever encryption/checksum/compression kind it was
+        // created with.
+        let (enc_type, salt, checksum_kind, compression_kind) = if f.metadata()?.len() == 0 {
+            let checksum_kind = ChecksumKind::Crc32Ieee;
+            write_header(
+                &mut f,
+                EncryptionType::None,
+                &salt,
+                checksum_kind,
+                options.compression_kind,
+            )?;
+            (EncryptionType::None, salt, checksum_kind, options.compression_kind)
+        } else {
+            let (enc_type, salt, checksum_kind, compression_kind) =
+                read_header_descriptor(&mut f)?;
+            if enc_type != EncryptionType::None {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "file was created with encryption enabled; open it with open_encrypted instead",
+                ));
+            }
+            (enc_type, salt, checksum_kind, compression_kind)
+        };
+
         Ok(SimpleKV {
             f,
+            path: path.to_path_buf(),
             index: HashMap::new(),
+            enc_type,
+            salt,
+            key: None,
+            checksum_kind,
+            compression_kind,
+            compression_threshold: options.compression_threshold,
         })
     }
 
+    // Open (or create) a store whose record bodies are encrypted at rest. The
+    // 256-bit key is derived from `passphrase` with Argon2id, using a random
+    // salt generated on first creation and persisted in the header thereafter.
+    pub fn open_encrypted(path: &Path, passphrase: &str) -> io::Result<Self> {
+        let mut f = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        let is_new = f.metadata()?.len() == 0;
+
+        let (enc_type, salt, checksum_kind, compression_kind) = if is_new {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            let enc_type = EncryptionType::AesGcm;
+            let checksum_kind = ChecksumKind::Crc32Ieee;
+            let compression_kind = CompressionKind::None;
+            write_header(&mut f, enc_type, &salt, checksum_kind, compression_kind)?;
+            (enc_type, salt, checksum_kind, compression_kind)
+        } else {
+            let (enc_type, salt, checksum_kind, compression_kind) =
+                read_header_descriptor(&mut f)?;
+            if enc_type == EncryptionType::None {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "file was not created with encryption enabled",
+                ));
+            }
+            (enc_type, salt, checksum_kind, compression_kind)
+        };
+
+        let key = derive_key(passphrase.as_bytes(), &salt)?;
+
+        Ok(SimpleKV {
+            f,
+            path: path.to_path_buf(),
+            index: HashMap::new(),
+            enc_type,
+            salt,
+            key: Some(key),
+            checksum_kind,
+            compression_kind,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        })
+    }
+
+    // Open (or create) a store using a specific CRC variant for record
+    // integrity checks. Only takes effect for a brand-new file; an existing
+    // file keeps whatever checksum kind it was created with.
+    pub fn open_with_checksum(path: &Path, checksum_kind: ChecksumKind) -> io::Result<Self> {
+        let mut f = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        let salt = [0u8; SALT_LEN];
+
+        let (enc_type, salt, checksum_kind, compression_kind) = if f.metadata()?.len() == 0 {
+            write_header(
+                &mut f,
+                EncryptionType::None,
+                &salt,
+                checksum_kind,
+                CompressionKind::None,
+            )?;
+            (EncryptionType::None, salt, checksum_kind, CompressionKind::None)
+        } else {
+            let (enc_type, salt, checksum_kind, compression_kind) =
+                read_header_descriptor(&mut f)?;
+            if enc_type != EncryptionType::None {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "file was created with encryption enabled; open it with open_encrypted instead",
+                ));
+            }
+            (enc_type, salt, checksum_kind, compression_kind)
+        };
+
+        Ok(SimpleKV {
+            f,
+            path: path.to_path_buf(),
+            index: HashMap::new(),
+            enc_type,
+            salt,
+            key: None,
+            checksum_kind,
+            compression_kind,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        })
+    }
+
+    // Verify the file signature, format version and encryption mode before
+    // scanning records.
+    fn verify_header(&mut self) -> io::Result<()> {
+        let (enc_type, _salt, checksum_kind, compression_kind) =
+            read_header_descriptor(&mut self.f)?;
+        if enc_type != self.enc_type {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "file encryption mode does not match how it was opened",
+            ));
+        }
+
+        self.checksum_kind = checksum_kind;
+        self.compression_kind = compression_kind;
+
+        Ok(())
+    }
+
     // Populate the index with mapping.
     pub fn load(&mut self) -> io::Result<()> {
+        self.verify_header()?;
+
+        let enc_type = self.enc_type;
+        let key = self.key;
+        let checksum_kind = self.checksum_kind;
+        let compression_kind = self.compression_kind;
+
         let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(HEADER_LEN))?;
 
         loop {
-            // Number of bytes from the start of the file. This is used as value of the index.
-            let current_position = f.seek(SeekFrom::Current(0))?;
+            // Number of bytes from the end of the header. This is used as value of the index.
+            let current_position = f.stream_position()? - HEADER_LEN;
 
             // Read a record in the file at its current position.
-            let maybe_kv = SimpleKV::process_record(&mut f);
+            let maybe_kv = SimpleKV::process_record(
+                &mut f,
+                enc_type,
+                key.as_ref(),
+                checksum_kind,
+                compression_kind,
+            );
             let kv = match maybe_kv {
                 Ok(kv) => kv,
                 Err(err) => match err.kind() {
@@ -60,59 +568,113 @@ impl SimpleKV {
                 },
             };
 
-            self.index.insert(kv.key, current_position);
+            match kv.record_type {
+                RecordType::Live => {
+                    self.index.insert(kv.key, current_position);
+                }
+                RecordType::Tombstone => {
+                    self.index.remove(&kv.key);
+                }
+            }
         }
 
         Ok(())
     }
 
     // Use "Bitcask" file format for processing records:
-    //  1. Read twelve bytes that represents a checksum, key length and value length.
+    //  1. Read fourteen bytes that represents a checksum, key length, value
+    //     length, record type and compression flag.
     //  2. Read the rest of the data from disk and verify it.
     //
-    //  Fixed-width header   Variable-length body
-    //  -----------------   --------------------------
-    // /                 \/                           \
-    // +=====+=====+=====+====== - - +============= - - +
-    // | u32 | u32 | u32 | [u8]      | [u8]             |
-    // +=====+=====+=====+====== - - +============= - - +
+    //  Fixed-width header              Variable-length body
+    //  ------------------------------  --------------------------
+    // /                              \/                           \
+    // +=====+=====+=====+=====+=====+====== - - +============= - - +
+    // | u32 | u32 | u32 | u8  | u8  | [u8]      | [u8]             |
+    // +=====+=====+=====+=====+=====+====== - - +============= - - +
     // checksum (4 bytes)
     // key_len (4 bytes)
-    // val_len (4 bytes)
-    // key (key_len bytes)
-    // value (val_len bytes)
-    fn process_record<R: Read>(f: &mut R) -> io::Result<KeyValuePair> {
+    // val_len (4 bytes, length of the value as stored, i.e. after compression)
+    // record_type (1 byte, LIVE = 0, TOMBSTONE = 1)
+    // compressed (1 byte, 0 = value stored as-is, 1 = value was compressed)
+    // body (key_len + val_len bytes, plaintext; or, when encrypted,
+    //       a 12-byte nonce followed by the AEAD ciphertext of key || value)
+    //
+    // The checksum covers the record type and compression flag bytes together
+    // with the on-disk body, so corruption is caught before any decryption or
+    // decompression is attempted.
+    fn process_record<R: Read>(
+        f: &mut R,
+        enc_type: EncryptionType,
+        key: Option<&[u8; 32]>,
+        checksum_kind: ChecksumKind,
+        compression_kind: CompressionKind,
+    ) -> io::Result<KeyValuePair> {
         let saved_checksum = f.read_u32::<LittleEndian>()?;
         let key_len = f.read_u32::<LittleEndian>()?;
         let val_len = f.read_u32::<LittleEndian>()?;
-        let data_len = key_len + val_len;
+        let record_type = RecordType::from_byte(f.read_u8()?)?;
+        let compressed = f.read_u8()? != 0;
 
-        let mut data = ByteString::with_capacity(data_len as usize);
+        let body_len = match enc_type {
+            EncryptionType::None => key_len + val_len,
+            EncryptionType::AesGcm | EncryptionType::ChaCha20Poly1305 => {
+                NONCE_LEN as u32 + key_len + val_len + AEAD_TAG_LEN as u32
+            }
+        };
+
+        let mut body = ByteString::with_capacity(body_len as usize);
 
         {
             // Sidestep ownership issues by using short-lived scope.
-            f.by_ref().take(data_len as u64).read_to_end(&mut data)?;
+            f.by_ref().take(body_len as u64).read_to_end(&mut body)?;
         }
 
         // This test is disabled in optimized build.
-        debug_assert_eq!(data.len(), data_len as usize);
+        debug_assert_eq!(body.len(), body_len as usize);
+
+        let mut checksummed = ByteString::with_capacity(body.len() + 2);
+        checksummed.push(record_type.to_byte());
+        checksummed.push(compressed as u8);
+        checksummed.extend_from_slice(&body);
 
-        let checksum = crc32::checksum_ieee(&data);
+        let checksum = compute_checksum(checksum_kind, &checksummed);
         if checksum != saved_checksum {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!(
-                    "data corruption encountered ({:08x} != {:08x})",
-                    checksum, saved_checksum
-                ),
-            ));
+            return Err(std::io::Error::other(format!(
+                "data corruption encountered ({:08x} != {:08x})",
+                checksum, saved_checksum
+            )));
         }
 
+        let mut data = match enc_type {
+            EncryptionType::None => body,
+            EncryptionType::AesGcm | EncryptionType::ChaCha20Poly1305 => {
+                let key = key.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "encrypted record but no key available to decrypt it",
+                    )
+                })?;
+                let nonce: [u8; NONCE_LEN] = body[..NONCE_LEN].try_into().unwrap();
+                decrypt_body(enc_type, key, &nonce, &body[NONCE_LEN..])?
+            }
+        };
+
         // Split data Vec<u8> in two at key_len.
-        let value = data.split_off(key_len as usize);
+        let stored_value = data.split_off(key_len as usize);
         let key = data;
 
-        Ok(KeyValuePair { key, value })
+        let value = if compressed {
+            decompress_value(compression_kind, &stored_value)?
+        } else {
+            stored_value
+        };
+
+        Ok(KeyValuePair {
+            key,
+            value,
+            record_type,
+        })
     }
 
     pub fn insert(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<()> {
@@ -124,33 +686,84 @@ impl SimpleKV {
     }
 
     pub fn insert_but_ignore_index(&mut self, key: &ByteStr, value: &ByteStr) -> io::Result<u64> {
-        // BufWriter batches multiple short write() calls into fewer actual disk operations to
-        // increase throughput.
-        let mut f = BufWriter::new(&mut self.f);
+        self.write_record(RecordType::Live, key, value)
+    }
+
+    fn write_record(
+        &mut self,
+        record_type: RecordType,
+        key: &ByteStr,
+        value: &ByteStr,
+    ) -> io::Result<u64> {
+        let enc_type = self.enc_type;
+        let enc_key = self.key;
+        let checksum_kind = self.checksum_kind;
+        let compression_kind = self.compression_kind;
+        let compression_threshold = self.compression_threshold;
 
         let key_len = key.len();
-        let val_len = value.len();
+
+        let (stored_value, compressed) = if compression_kind != CompressionKind::None
+            && value.len() > compression_threshold
+        {
+            (compress_value(compression_kind, value)?, true)
+        } else {
+            (value.to_vec(), false)
+        };
+        let val_len = stored_value.len();
+
         let mut tmp = ByteString::with_capacity(key_len + val_len);
 
         for byte in key {
             tmp.push(*byte);
         }
 
-        for byte in value {
+        for byte in &stored_value {
             tmp.push(*byte);
         }
 
-        let checksum = crc32::checksum_ieee(&tmp);
+        let body = match enc_type {
+            EncryptionType::None => tmp,
+            EncryptionType::AesGcm | EncryptionType::ChaCha20Poly1305 => {
+                let enc_key = enc_key.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "store is marked as encrypted but has no derived key",
+                    )
+                })?;
+
+                let mut nonce = [0u8; NONCE_LEN];
+                OsRng.fill_bytes(&mut nonce);
+                let ciphertext = encrypt_body(enc_type, &enc_key, &nonce, &tmp)?;
+
+                let mut body = ByteString::with_capacity(NONCE_LEN + ciphertext.len());
+                body.extend_from_slice(&nonce);
+                body.extend_from_slice(&ciphertext);
+                body
+            }
+        };
+
+        let mut checksummed = ByteString::with_capacity(body.len() + 2);
+        checksummed.push(record_type.to_byte());
+        checksummed.push(compressed as u8);
+        checksummed.extend_from_slice(&body);
+        let checksum = compute_checksum(checksum_kind, &checksummed);
+
+        // BufWriter batches multiple short write() calls into fewer actual disk operations to
+        // increase throughput.
+        let mut f = BufWriter::new(&mut self.f);
 
         let next_byte = SeekFrom::End(0);
-        let current_position = f.seek(SeekFrom::Current(0))?;
+        let current_position = f.stream_position()?;
         f.seek(next_byte)?;
         f.write_u32::<LittleEndian>(checksum)?;
         f.write_u32::<LittleEndian>(key_len as u32)?;
         f.write_u32::<LittleEndian>(val_len as u32)?;
-        f.write_all(&tmp)?;
+        f.write_u8(record_type.to_byte())?;
+        f.write_u8(compressed as u8)?;
+        f.write_all(&body)?;
 
-        Ok(current_position)
+        Ok(current_position - HEADER_LEN)
     }
 
     pub fn get(&mut self, key: &ByteStr) -> io::Result<Option<ByteString>> {
@@ -165,22 +778,45 @@ impl SimpleKV {
     }
 
     pub fn get_at(&mut self, position: u64) -> io::Result<KeyValuePair> {
+        let enc_type = self.enc_type;
+        let key = self.key;
+        let checksum_kind = self.checksum_kind;
+        let compression_kind = self.compression_kind;
+
         let mut f = BufReader::new(&mut self.f);
-        f.seek(SeekFrom::Start(position))?;
-        let kv = SimpleKV::process_record(&mut f)?;
+        f.seek(SeekFrom::Start(position + HEADER_LEN))?;
+        let kv = SimpleKV::process_record(
+            &mut f,
+            enc_type,
+            key.as_ref(),
+            checksum_kind,
+            compression_kind,
+        )?;
 
         Ok(kv)
     }
 
     pub fn find(&mut self, target: &ByteStr) -> io::Result<Option<(u64, ByteString)>> {
+        let enc_type = self.enc_type;
+        let key = self.key;
+        let checksum_kind = self.checksum_kind;
+        let compression_kind = self.compression_kind;
+
         let mut f = BufReader::new(&mut self.f);
+        f.seek(SeekFrom::Start(HEADER_LEN))?;
 
         let mut found: Option<(u64, ByteString)> = None;
 
         loop {
-            let position = f.seek(SeekFrom::Current(0))?;
+            let position = f.stream_position()? - HEADER_LEN;
 
-            let maybe_kv = SimpleKV::process_record(&mut f);
+            let maybe_kv = SimpleKV::process_record(
+                &mut f,
+                enc_type,
+                key.as_ref(),
+                checksum_kind,
+                compression_kind,
+            );
             let kv = match maybe_kv {
                 Ok(kv) => kv,
                 Err(err) => match err.kind() {
@@ -192,7 +828,10 @@ impl SimpleKV {
             };
 
             if kv.key == target {
-                found = Some((position, kv.value));
+                found = match kv.record_type {
+                    RecordType::Live => Some((position, kv.value)),
+                    RecordType::Tombstone => None,
+                };
             }
 
             // Loop until the end of the file in case the key has been overwritten.
@@ -206,8 +845,437 @@ impl SimpleKV {
         self.insert(key, value)
     }
 
-    #[inline]
     pub fn delete(&mut self, key: &ByteStr) -> io::Result<()> {
-        self.insert(key, b"")
+        self.write_record(RecordType::Tombstone, key, b"")?;
+        self.index.remove(key);
+        Ok(())
+    }
+
+    // Rewrite the store into a fresh file containing only the live version of
+    // each key, then atomically swap it in. Stale and deleted records are
+    // reclaimed, so a long-lived store does not grow without bound.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("compact.tmp");
+
+        let mut tmp_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        write_header(
+            &mut tmp_file,
+            self.enc_type,
+            &self.salt,
+            self.checksum_kind,
+            self.compression_kind,
+        )?;
+
+        let mut new_index = HashMap::new();
+        let mut new_kv = SimpleKV {
+            f: tmp_file,
+            path: tmp_path.clone(),
+            index: HashMap::new(),
+            enc_type: self.enc_type,
+            salt: self.salt,
+            key: self.key,
+            checksum_kind: self.checksum_kind,
+            compression_kind: self.compression_kind,
+            compression_threshold: self.compression_threshold,
+        };
+
+        // Scan the file directly rather than trusting `self.index`, so a
+        // caller who invokes `compact` without ever calling `load` first
+        // rebuilds the live set from disk instead of truncating it away.
+        let enc_type = self.enc_type;
+        let key = self.key;
+        let checksum_kind = self.checksum_kind;
+        let compression_kind = self.compression_kind;
+
+        let mut live: HashMap<ByteString, ByteString> = HashMap::new();
+        {
+            let mut f = BufReader::new(&mut self.f);
+            f.seek(SeekFrom::Start(HEADER_LEN))?;
+
+            loop {
+                let maybe_kv = SimpleKV::process_record(
+                    &mut f,
+                    enc_type,
+                    key.as_ref(),
+                    checksum_kind,
+                    compression_kind,
+                );
+                let kv = match maybe_kv {
+                    Ok(kv) => kv,
+                    Err(err) => match err.kind() {
+                        io::ErrorKind::UnexpectedEof => break,
+                        _ => return Err(err),
+                    },
+                };
+
+                match kv.record_type {
+                    RecordType::Live => {
+                        live.insert(kv.key, kv.value);
+                    }
+                    RecordType::Tombstone => {
+                        live.remove(&kv.key);
+                    }
+                }
+            }
+        }
+
+        // Drop the `+index` blob rather than copying it over: it's a bincode
+        // snapshot of key -> byte offset produced by `store_index_on_disk`,
+        // and every offset in it is now wrong (tombstoned keys are gone and
+        // every surviving record has shifted). Dropping it forces the next
+        // `store_index_on_disk` call to regenerate it from a fresh `load`
+        // instead of compaction silently re-publishing stale offsets.
+        for (key, value) in live {
+            if key.as_slice() == INDEX_KEY {
+                continue;
+            }
+
+            let new_position = new_kv.insert_but_ignore_index(&key, &value)?;
+            new_index.insert(key, new_position);
+        }
+
+        new_kv.f.sync_all()?;
+        drop(new_kv.f);
+
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.f = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.index = new_index;
+
+        Ok(())
+    }
+
+    // Write every live key/value pair as a hex-encoded, binary-safe XML
+    // document. This is a portable, diffable backup format that does not
+    // depend on the exact binary record layout.
+    pub fn dump_xml<W: Write>(&mut self, mut out: W) -> io::Result<()> {
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(out, "<simplekv version=\"{}\">", FORMAT_VERSION)?;
+
+        let live_entries: Vec<(ByteString, u64)> = self
+            .index
+            .iter()
+            .map(|(key, &position)| (key.clone(), position))
+            .collect();
+
+        for (key, position) in live_entries {
+            if key.as_slice() == INDEX_KEY {
+                continue;
+            }
+
+            let kv = self.get_at(position)?;
+            writeln!(
+                out,
+                "  <entry key=\"{}\" value=\"{}\"/>",
+                hex_encode(&key),
+                hex_encode(&kv.value)
+            )?;
+        }
+
+        writeln!(out, "</simplekv>")?;
+        Ok(())
+    }
+
+    // Rebuild a fresh, compacted store at `path` by replaying `insert` for
+    // every `<entry>` in an XML document produced by `dump_xml`. Any existing
+    // file at `path` is overwritten.
+    pub fn restore_xml<R: Read>(path: &Path, mut input: R) -> io::Result<Self> {
+        let mut xml = String::new();
+        input.read_to_string(&mut xml)?;
+
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let salt = [0u8; SALT_LEN];
+        let checksum_kind = ChecksumKind::Crc32Ieee;
+        write_header(
+            &mut f,
+            EncryptionType::None,
+            &salt,
+            checksum_kind,
+            CompressionKind::None,
+        )?;
+
+        let mut store = SimpleKV {
+            f,
+            path: path.to_path_buf(),
+            index: HashMap::new(),
+            enc_type: EncryptionType::None,
+            salt,
+            key: None,
+            compression_kind: CompressionKind::None,
+            compression_threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            checksum_kind,
+        };
+
+        for line in xml.lines() {
+            let line = line.trim();
+            if !line.starts_with("<entry ") {
+                continue;
+            }
+
+            let key = extract_hex_attr(line, "key")?;
+            let value = extract_hex_attr(line, "value")?;
+            store.insert(&key, &value)?;
+        }
+
+        Ok(store)
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        out.push_str(&format!("{:02x}", byte));
+    }
+    out
+}
+
+fn hex_decode(s: &str) -> io::Result<ByteString> {
+    if !s.len().is_multiple_of(2) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "odd-length hex string",
+        ));
+    }
+
+    let digit = |byte: u8| -> io::Result<u8> {
+        match byte {
+            b'0'..=b'9' => Ok(byte - b'0'),
+            b'a'..=b'f' => Ok(byte - b'a' + 10),
+            b'A'..=b'F' => Ok(byte - b'A' + 10),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "invalid hex digit")),
+        }
+    };
+
+    let bytes = s.as_bytes();
+    let mut out = ByteString::with_capacity(bytes.len() / 2);
+    for chunk in bytes.chunks(2) {
+        out.push((digit(chunk[0])? << 4) | digit(chunk[1])?);
+    }
+
+    Ok(out)
+}
+
+// Pull the hex-encoded value of `<entry key="..." value="..."/>`'s `name`
+// attribute out of a single XML line.
+fn extract_hex_attr(line: &str, name: &str) -> io::Result<ByteString> {
+    let needle = format!("{}=\"", name);
+    let start = line
+        .find(&needle)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("missing {} attribute", name),
+            )
+        })?
+        + needle.len();
+    let end = line[start..]
+        .find('"')
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unterminated {} attribute", name),
+            )
+        })?
+        + start;
+
+    hex_decode(&line[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    // Each test gets its own file under the OS temp dir so runs don't collide.
+    fn temp_path(name: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(format!(
+            "simplekv_test_{}_{}_{}.db",
+            std::process::id(),
+            n,
+            name
+        ))
+    }
+
+    #[test]
+    fn round_trip_plaintext() {
+        let path = temp_path("plaintext");
+        let mut store = SimpleKV::open(&path).unwrap();
+        store.insert(b"hello", b"world").unwrap();
+        assert_eq!(store.get(b"hello").unwrap(), Some(b"world".to_vec()));
+
+        let mut reopened = SimpleKV::open(&path).unwrap();
+        reopened.load().unwrap();
+        assert_eq!(reopened.get(b"hello").unwrap(), Some(b"world".to_vec()));
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn round_trip_encrypted() {
+        for enc_type_name in ["aes-gcm", "chacha20poly1305"] {
+            let path = temp_path(enc_type_name);
+            let mut store = SimpleKV::open_encrypted(&path, "correct horse battery staple").unwrap();
+            store.insert(b"secret", b"value").unwrap();
+
+            let mut reopened =
+                SimpleKV::open_encrypted(&path, "correct horse battery staple").unwrap();
+            reopened.load().unwrap();
+            assert_eq!(reopened.get(b"secret").unwrap(), Some(b"value".to_vec()));
+
+            fs::remove_file(&path).ok();
+        }
+    }
+
+    #[test]
+    fn open_with_rejects_encrypted_file() {
+        let path = temp_path("mismatched-open");
+        SimpleKV::open_encrypted(&path, "correct horse battery staple")
+            .unwrap()
+            .insert(b"secret", b"value")
+            .unwrap();
+
+        assert!(SimpleKV::open(&path).is_err());
+        assert!(SimpleKV::open_with_checksum(&path, ChecksumKind::Crc32c).is_err());
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn round_trip_checksum_kinds() {
+        for checksum_kind in [ChecksumKind::Crc32Ieee, ChecksumKind::Crc32c] {
+            let path = temp_path("checksum");
+            let mut store = SimpleKV::open_with_checksum(&path, checksum_kind).unwrap();
+            store.insert(b"key", b"value").unwrap();
+
+            let mut reopened = SimpleKV::open_with_checksum(&path, checksum_kind).unwrap();
+            reopened.load().unwrap();
+            assert_eq!(reopened.get(b"key").unwrap(), Some(b"value".to_vec()));
+
+            fs::remove_file(&path).ok();
+        }
+    }
+
+    #[test]
+    fn round_trip_compression_kinds() {
+        for compression_kind in [CompressionKind::Zstd, CompressionKind::Deflate] {
+            let path = temp_path("compression");
+            let options = SimpleKVOptions::new()
+                .compression(compression_kind)
+                .compression_threshold(8);
+            let mut store = SimpleKV::open_with(&path, options).unwrap();
+
+            let value = vec![b'x'; 4096];
+            store.insert(b"big", &value).unwrap();
+
+            let mut reopened = SimpleKV::open_with(&path, options).unwrap();
+            reopened.load().unwrap();
+            assert_eq!(reopened.get(b"big").unwrap(), Some(value));
+
+            fs::remove_file(&path).ok();
+        }
+    }
+
+    #[test]
+    fn corrupted_record_is_rejected_before_use() {
+        let path = temp_path("corrupt");
+        let mut store = SimpleKV::open_encrypted(&path, "passphrase").unwrap();
+        store.insert(b"key", b"value").unwrap();
+        drop(store);
+
+        // Flip a byte inside the first record's on-disk body (past the
+        // checksum/length/flag header) to simulate corruption on disk.
+        let mut raw = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        raw.seek(SeekFrom::Start(HEADER_LEN + 14)).unwrap();
+        let mut byte = [0u8; 1];
+        raw.read_exact(&mut byte).unwrap();
+        raw.seek(SeekFrom::Start(HEADER_LEN + 14)).unwrap();
+        raw.write_all(&[byte[0] ^ 0xff]).unwrap();
+        drop(raw);
+
+        let mut reopened = SimpleKV::open_encrypted(&path, "passphrase").unwrap();
+        let err = reopened.load().unwrap_err();
+        assert!(err.to_string().contains("data corruption"));
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn tombstones_do_not_resurrect_after_compact() {
+        let path = temp_path("compact_tombstone");
+        let mut store = SimpleKV::open(&path).unwrap();
+        store.insert(b"key", b"value").unwrap();
+        store.delete(b"key").unwrap();
+        assert_eq!(store.get(b"key").unwrap(), None);
+
+        store.compact().unwrap();
+        assert_eq!(store.get(b"key").unwrap(), None);
+
+        let mut reopened = SimpleKV::open(&path).unwrap();
+        reopened.load().unwrap();
+        assert_eq!(reopened.get(b"key").unwrap(), None);
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn dump_and_restore_xml_round_trip() {
+        let path = temp_path("dump_xml");
+        let mut store = SimpleKV::open(&path).unwrap();
+        store.insert(b"alpha", b"one").unwrap();
+        store.insert(b"beta", b"two").unwrap();
+        store.delete(b"beta").unwrap();
+        store.load().unwrap();
+
+        let mut dump = Vec::new();
+        store.dump_xml(&mut dump).unwrap();
+
+        let restore_path = temp_path("restore_xml");
+        let mut restored = SimpleKV::restore_xml(&restore_path, dump.as_slice()).unwrap();
+        assert_eq!(restored.get(b"alpha").unwrap(), Some(b"one".to_vec()));
+        assert_eq!(restored.get(b"beta").unwrap(), None);
+
+        fs::remove_file(&path).ok();
+        fs::remove_file(&restore_path).ok();
+    }
+
+    #[test]
+    fn compact_drops_stale_index_blob() {
+        let path = temp_path("compact_index");
+        let mut store = SimpleKV::open(&path).unwrap();
+        store.insert(b"key", b"value").unwrap();
+
+        // Stand in for what skv_disk's store_index_on_disk would have left
+        // behind: a bincode-encoded offset map pointing at positions that
+        // compaction is about to invalidate.
+        let stale_offsets: HashMap<ByteString, u64> =
+            HashMap::from([(b"key".to_vec(), 0u64)]);
+        store
+            .insert(INDEX_KEY, &bincode::serialize(&stale_offsets).unwrap())
+            .unwrap();
+
+        store.compact().unwrap();
+
+        // The blob must not survive compaction with unchanged (now-wrong)
+        // offsets; the next store_index_on_disk call has to regenerate it.
+        assert_eq!(store.get(INDEX_KEY).unwrap(), None);
+        assert_eq!(store.get(b"key").unwrap(), Some(b"value".to_vec()));
+
+        fs::remove_file(&path).ok();
     }
 }
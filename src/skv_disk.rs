@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::fs::File;
 
 use libsimplekv::SimpleKV;
 
@@ -8,6 +9,8 @@ Usage:
     skv_mem FILE delete KEY
     skv_mem FILE insert KEY VALUE
     skv_mem FILE update KEY VALUE
+    skv_mem FILE dump OUTFILE
+    skv_mem FILE restore INFILE
 ";
 
 type ByteStr = [u8];
@@ -24,21 +27,42 @@ fn main() {
     const INDEX_KEY: &ByteStr = b"+index";
 
     let args: Vec<String> = std::env::args().collect();
-    let fname = args.get(1).expect(&USAGE);
-    let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+    let fname = args.get(1).expect(USAGE);
+    let action = args.get(2).expect(USAGE).as_ref();
+    let key = args.get(3).expect(USAGE).as_ref();
     let maybe_value = args.get(4);
 
     let path = std::path::Path::new(&fname);
     let mut store = SimpleKV::open(path).expect("unable to open file");
 
     store.load().expect("unable to load data");
+
+    // `dump`/`restore` need the real index built by `load`, not the
+    // single-entry stand-in `store_index_on_disk` leaves behind below, so
+    // handle them before that rewrite happens.
+    match action {
+        "dump" => {
+            let out_path = args.get(3).expect(USAGE);
+            let out_file = File::create(out_path).expect("unable to create dump file");
+            store.dump_xml(out_file).expect("unable to dump store");
+            return;
+        }
+        "restore" => {
+            let in_path = args.get(3).expect(USAGE);
+            let in_file = File::open(in_path).expect("unable to open dump file");
+            drop(store);
+            SimpleKV::restore_xml(path, in_file).expect("unable to restore store");
+            return;
+        }
+        _ => {}
+    }
+
     store_index_on_disk(&mut store, INDEX_KEY);
 
     match action {
         "get" => {
             // Two unwraps are required becase a.index is a HashMap that returns Option with Option values.
-            let index_as_bytes = store.get(&INDEX_KEY).unwrap().unwrap();
+            let index_as_bytes = store.get(INDEX_KEY).unwrap().unwrap();
 
             // Convert the on-disk representation to an in-memory representation.
             let index: HashMap<ByteString, u64> = bincode::deserialize(&index_as_bytes).unwrap();
@@ -51,11 +75,11 @@ fn main() {
         }
         "delete" => store.delete(key).unwrap(),
         "insert" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             store.insert(key, value).unwrap()
         }
         "update" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             store.update(key, value).unwrap()
         }
         _ => eprintln!("{}", &USAGE),
@@ -1,3 +1,5 @@
+use std::fs::File;
+
 use libsimplekv::SimpleKV;
 
 const USAGE: &str = "
@@ -6,13 +8,15 @@ Usage:
     skv_mem FILE delete KEY
     skv_mem FILE insert KEY VALUE
     skv_mem FILE update KEY VALUE
+    skv_mem FILE dump OUTFILE
+    skv_mem FILE restore INFILE
 ";
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let fname = args.get(1).expect(&USAGE);
-    let action = args.get(2).expect(&USAGE).as_ref();
-    let key = args.get(3).expect(&USAGE).as_ref();
+    let fname = args.get(1).expect(USAGE);
+    let action = args.get(2).expect(USAGE).as_ref();
+    let key = args.get(3).expect(USAGE).as_ref();
     let maybe_value = args.get(4);
 
     let path = std::path::Path::new(&fname);
@@ -26,13 +30,24 @@ fn main() {
         },
         "delete" => store.delete(key).unwrap(),
         "insert" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             store.insert(key, value).unwrap()
         }
         "update" => {
-            let value = maybe_value.expect(&USAGE).as_ref();
+            let value = maybe_value.expect(USAGE).as_ref();
             store.update(key, value).unwrap()
         }
+        "dump" => {
+            let out_path = args.get(3).expect(USAGE);
+            let out_file = File::create(out_path).expect("unable to create dump file");
+            store.dump_xml(out_file).expect("unable to dump store");
+        }
+        "restore" => {
+            let in_path = args.get(3).expect(USAGE);
+            let in_file = File::open(in_path).expect("unable to open dump file");
+            drop(store);
+            SimpleKV::restore_xml(path, in_file).expect("unable to restore store");
+        }
         _ => eprintln!("{}", &USAGE),
     }
 }